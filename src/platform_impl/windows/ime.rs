@@ -1,31 +1,105 @@
 use std::{
     ffi::{c_void, OsString},
     mem::zeroed,
+    ops::Range,
     os::windows::prelude::OsStringExt,
     ptr::null_mut,
 };
 
 use windows_sys::Win32::{
-    Foundation::POINT,
+    Foundation::{POINT, RECT, WPARAM},
     Globalization::HIMC,
     UI::{
         Input::Ime::{
-            ImmGetCandidateListW, ImmAssociateContextEx, ImmGetCompositionStringW, ImmGetContext, ImmReleaseContext,
-            ImmSetCandidateWindow, ATTR_TARGET_CONVERTED, ATTR_TARGET_NOTCONVERTED, CANDIDATEFORM,
-            CFS_EXCLUDE, GCS_COMPATTR, GCS_COMPSTR, GCS_CURSORPOS, GCS_RESULTSTR, IACE_CHILDREN,
-            IACE_DEFAULT, CANDIDATELIST
+            ImmAssociateContextEx, ImmGetCandidateListW, ImmGetCompositionStringW, ImmGetContext,
+            ImmGetConversionStatus, ImmGetOpenStatus, ImmReleaseContext, ImmSetCandidateWindow,
+            ImmSetCompositionWindow, ImmSetConversionStatus, ImmSetOpenStatus,
+            ATTR_TARGET_CONVERTED, ATTR_TARGET_NOTCONVERTED, CANDIDATEFORM, CANDIDATELIST,
+            CFS_EXCLUDE, CFS_POINT, COMPOSITIONFORM, GCS_COMPATTR, GCS_COMPCLAUSE, GCS_COMPSTR,
+            GCS_CURSORPOS, GCS_RESULTSTR, IACE_CHILDREN, IACE_DEFAULT, IME_CMODE_FULLSHAPE,
+            IME_CMODE_KATAKANA, IME_CMODE_NATIVE, IME_CMODE_ROMAN, IMN_CHANGECANDIDATE,
+            IMN_OPENCANDIDATE, IMN_SETCONVERSIONMODE, IMN_SETOPENSTATUS, IMN_SETSENTENCEMODE,
         },
         WindowsAndMessaging::{GetSystemMetrics, SM_IMMENABLED},
     },
 };
 
-use crate::{dpi::Position, platform::windows::HWND};
+use crate::dpi::{Position, Size};
+use crate::platform::windows::HWND;
 
 pub struct ImeContext {
     hwnd: HWND,
     himc: HIMC,
 }
 
+/// A candidate window snapshot: the candidates, which one is selected, and
+/// the `page_start..page_start + page_size` slice of `items` currently shown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidateList {
+    pub items: Vec<String>,
+    pub selection: usize,
+    pub page_start: usize,
+    pub page_size: usize,
+}
+
+/// Pushed IME state changes, driven by `WM_IME_NOTIFY`, that an application
+/// would otherwise have to poll `ImeContext` for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImeRequestEvent {
+    CandidateList(CandidateList),
+    ConversionModeChanged(ConversionMode),
+    OpenStatusChanged(bool),
+}
+
+const KNOWN_CMODE_BITS: u32 =
+    IME_CMODE_NATIVE | IME_CMODE_KATAKANA | IME_CMODE_FULLSHAPE | IME_CMODE_ROMAN;
+
+/// The decoded `fdwConversion`/`fdwSentence` returned by `ImmGetConversionStatus`.
+///
+/// `unknown_conversion_bits` keeps any `fdwConversion` bits this struct
+/// doesn't model (`IME_CMODE_SYMBOL`, `IME_CMODE_SOFTKBD`, etc.) so that
+/// `get_conversion_mode` followed by `set_conversion_mode` round-trips
+/// without silently clearing IME state the caller never touched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ConversionMode {
+    pub native: bool,
+    pub katakana: bool,
+    pub full_shape: bool,
+    pub roman: bool,
+    pub sentence: u32,
+    unknown_conversion_bits: u32,
+}
+
+impl ConversionMode {
+    fn from_raw(fdw_conversion: u32, fdw_sentence: u32) -> Self {
+        ConversionMode {
+            native: fdw_conversion & IME_CMODE_NATIVE != 0,
+            katakana: fdw_conversion & IME_CMODE_KATAKANA != 0,
+            full_shape: fdw_conversion & IME_CMODE_FULLSHAPE != 0,
+            roman: fdw_conversion & IME_CMODE_ROMAN != 0,
+            sentence: fdw_sentence,
+            unknown_conversion_bits: fdw_conversion & !KNOWN_CMODE_BITS,
+        }
+    }
+
+    fn to_raw(self) -> (u32, u32) {
+        let mut fdw_conversion = self.unknown_conversion_bits;
+        if self.native {
+            fdw_conversion |= IME_CMODE_NATIVE;
+        }
+        if self.katakana {
+            fdw_conversion |= IME_CMODE_KATAKANA;
+        }
+        if self.full_shape {
+            fdw_conversion |= IME_CMODE_FULLSHAPE;
+        }
+        if self.roman {
+            fdw_conversion |= IME_CMODE_ROMAN;
+        }
+        (fdw_conversion, self.sentence)
+    }
+}
+
 impl ImeContext {
     pub unsafe fn current(hwnd: HWND) -> Self {
         let himc = ImmGetContext(hwnd);
@@ -67,11 +141,49 @@ impl ImeContext {
         Some((text, first, last))
     }
 
+    /// Like [`ImeContext::get_composing_text_and_cursor`], plus clause boundaries.
+    pub unsafe fn get_composing_text_cursor_and_clauses(
+        &self,
+    ) -> Option<(String, Option<usize>, Option<usize>, Vec<Range<usize>>)> {
+        let (text, first, last) = self.get_composing_text_and_cursor()?;
+        let clauses = self.get_composition_clauses(&text);
+        Some((text, first, last, clauses))
+    }
+
+    unsafe fn get_composition_clauses(&self, text: &str) -> Vec<Range<usize>> {
+        let raw = self
+            .get_composition_data(GCS_COMPCLAUSE)
+            .unwrap_or_default();
+        let (prefix, offsets, suffix) = raw.align_to::<u32>();
+
+        if !prefix.is_empty() || !suffix.is_empty() || offsets.len() < 2 {
+            return vec![0..text.len()];
+        }
+
+        offsets
+            .windows(2)
+            .map(|w| Self::utf16_offset_to_utf8(text, w[0])..Self::utf16_offset_to_utf8(text, w[1]))
+            .collect()
+    }
+
+    /// Converts a UTF-16 code-unit offset (as reported by
+    /// `ImmGetCompositionStringW`) into a UTF-8 byte offset into `text`.
+    fn utf16_offset_to_utf8(text: &str, utf16_offset: u32) -> usize {
+        let mut utf16_count = 0u32;
+        for (utf8_idx, chr) in text.char_indices() {
+            if utf16_count >= utf16_offset {
+                return utf8_idx;
+            }
+            utf16_count += chr.len_utf16() as u32;
+        }
+        text.len()
+    }
+
     pub unsafe fn get_composed_text(&self) -> Option<String> {
         self.get_composition_string(GCS_RESULTSTR)
     }
 
-    pub unsafe fn get_candidate_list(&self) -> Option<Vec<String>> {
+    pub unsafe fn get_candidate_list(&self) -> Option<CandidateList> {
         let size = ImmGetCandidateListW(self.himc, 0, std::ptr::null_mut(), 0) as usize;
         if size == 0 {
             return None;
@@ -83,16 +195,73 @@ impl ImeContext {
         }
         buf.set_len(size);
         let obj = &*(buf.as_ptr() as *const CANDIDATELIST);
-        let mut list: Vec<String> = Vec::with_capacity(obj.dwCount as usize);
+        let mut items: Vec<String> = Vec::with_capacity(obj.dwCount as usize);
         for i in 0..(obj.dwCount as usize) {
             let offset =
                 std::slice::from_raw_parts(&obj.dwOffset as *const u32, obj.dwCount as usize);
             let p = buf.as_ptr().offset(offset[i] as isize) as *const u16;
             let len = (0..isize::MAX).position(|i| *p.offset(i) == 0).unwrap();
             let slice = std::slice::from_raw_parts(p, len);
-            list.push(String::from_utf16_lossy(slice));
+            items.push(String::from_utf16_lossy(slice));
+        }
+
+        // Some IMEs report a stale or oversized dwPageStart/dwPageSize (e.g. the
+        // candidate count shrinks mid-composition), so clamp both against
+        // items.len() to keep page_start..page_start + page_size in range.
+        let page_start = (obj.dwPageStart as usize).min(items.len());
+        let page_size = if obj.dwPageSize == 0 {
+            items.len() - page_start
+        } else {
+            (obj.dwPageSize as usize).min(items.len() - page_start)
+        };
+
+        // dwSelection can likewise be stale, or dwCount itself (the IME's
+        // "nothing selected" sentinel), so clamp it into items the same way.
+        let selection = (obj.dwSelection as usize).min(items.len().saturating_sub(1));
+
+        Some(CandidateList {
+            items,
+            selection,
+            page_start,
+            page_size,
+        })
+    }
+
+    /// Decodes a `WM_IME_NOTIFY` message into the event it should produce, if
+    /// any. Called from the window procedure on every `WM_IME_NOTIFY`.
+    pub unsafe fn handle_ime_notify(&self, wparam: WPARAM) -> Option<ImeRequestEvent> {
+        match wparam as u32 {
+            IMN_OPENCANDIDATE | IMN_CHANGECANDIDATE => self
+                .get_candidate_list()
+                .map(ImeRequestEvent::CandidateList),
+            IMN_SETCONVERSIONMODE | IMN_SETSENTENCEMODE => self
+                .get_conversion_mode()
+                .map(ImeRequestEvent::ConversionModeChanged),
+            IMN_SETOPENSTATUS => Some(ImeRequestEvent::OpenStatusChanged(self.is_ime_open())),
+            _ => None,
+        }
+    }
+
+    pub unsafe fn get_conversion_mode(&self) -> Option<ConversionMode> {
+        if !ImeContext::system_has_ime() {
+            return None;
+        }
+
+        let mut fdw_conversion = 0;
+        let mut fdw_sentence = 0;
+        if ImmGetConversionStatus(self.himc, &mut fdw_conversion, &mut fdw_sentence) == 0 {
+            return None;
+        }
+        Some(ConversionMode::from_raw(fdw_conversion, fdw_sentence))
+    }
+
+    pub unsafe fn set_conversion_mode(&self, mode: ConversionMode) -> bool {
+        if !ImeContext::system_has_ime() {
+            return false;
         }
-        Some(list)
+
+        let (fdw_conversion, fdw_sentence) = mode.to_raw();
+        ImmSetConversionStatus(self.himc, fdw_conversion, fdw_sentence) != 0
     }
 
     unsafe fn get_composition_cursor(&self, text: &str) -> Option<usize> {
@@ -133,17 +302,33 @@ impl ImeContext {
         }
     }
 
-    pub unsafe fn set_ime_position(&self, spot: Position, scale_factor: f64) {
+    /// Positions the composition window and the candidate window, excluding
+    /// the caret rect described by `position`/`size` from the latter.
+    pub unsafe fn set_ime_position(&self, position: Position, size: Size, scale_factor: f64) {
         if !ImeContext::system_has_ime() {
             return;
         }
 
-        let (x, y) = spot.to_physical::<i32>(scale_factor).into();
+        let (x, y) = position.to_physical::<i32>(scale_factor).into();
+        let (width, height): (i32, i32) = size.to_physical::<i32>(scale_factor).into();
+
+        let composition_form = COMPOSITIONFORM {
+            dwStyle: CFS_POINT,
+            ptCurrentPos: POINT { x, y },
+            rcArea: zeroed(),
+        };
+        ImmSetCompositionWindow(self.himc, &composition_form);
+
         let candidate_form = CANDIDATEFORM {
             dwIndex: 0,
             dwStyle: CFS_EXCLUDE,
             ptCurrentPos: POINT { x, y },
-            rcArea: zeroed(),
+            rcArea: RECT {
+                left: x,
+                top: y,
+                right: x + width,
+                bottom: y + height,
+            },
         };
 
         ImmSetCandidateWindow(self.himc, &candidate_form);
@@ -161,6 +346,20 @@ impl ImeContext {
         }
     }
 
+    /// Returns `true` if the IME is currently open (actively composing).
+    pub unsafe fn is_ime_open(&self) -> bool {
+        ImeContext::system_has_ime() && ImmGetOpenStatus(self.himc) != 0
+    }
+
+    /// Forces the IME open or closed.
+    pub unsafe fn set_ime_open(&self, open: bool) {
+        if !ImeContext::system_has_ime() {
+            return;
+        }
+
+        ImmSetOpenStatus(self.himc, open as i32);
+    }
+
     unsafe fn system_has_ime() -> bool {
         GetSystemMetrics(SM_IMMENABLED) != 0
     }